@@ -0,0 +1,613 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+pub type ClientId = u16;
+pub type TxnId = u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Txn {
+    Deposit {
+        client: ClientId,
+        tx: TxnId,
+        amount: Decimal,
+    },
+    Withdrawal {
+        client: ClientId,
+        tx: TxnId,
+        amount: Decimal,
+    },
+    Dispute {
+        client: ClientId,
+        tx: TxnId,
+    },
+    Resolve {
+        client: ClientId,
+        tx: TxnId,
+    },
+    Chargeback {
+        client: ClientId,
+        tx: TxnId,
+    },
+}
+
+/// Row shape of the input CSV, deserialized with `csv`/`serde` instead of
+/// positional `StringRecord` indexing so that column order and extra
+/// columns don't matter, only the header names do. `amount` is required
+/// (and must be positive) for deposit/withdrawal and must be absent for
+/// dispute/resolve/chargeback; that's enforced by `TryFrom` below rather
+/// than by the shape of this struct, since the CSV crate has no way to
+/// make a field conditionally required.
+#[derive(Debug, Deserialize)]
+pub struct TransactionRecord {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub client: ClientId,
+    pub tx: TxnId,
+    pub amount: Option<Decimal>,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TxnParseError {
+    #[error("unknown transaction type {0:?}")]
+    UnknownType(String),
+    #[error("{0} transactions require a positive amount")]
+    MissingAmount(&'static str),
+    #[error("{0} transactions must not carry an amount")]
+    UnexpectedAmount(&'static str),
+    #[error("amount must be greater than zero, got {0}")]
+    NonPositiveAmount(Decimal),
+}
+
+impl TryFrom<TransactionRecord> for Txn {
+    type Error = TxnParseError;
+
+    fn try_from(r: TransactionRecord) -> Result<Self, Self::Error> {
+        match r.type_.as_str() {
+            "deposit" => Ok(Txn::Deposit {
+                client: r.client,
+                tx: r.tx,
+                amount: positive_amount(r.amount, "deposit")?,
+            }),
+            "withdrawal" => Ok(Txn::Withdrawal {
+                client: r.client,
+                tx: r.tx,
+                amount: positive_amount(r.amount, "withdrawal")?,
+            }),
+            "dispute" => {
+                no_amount(r.amount, "dispute")?;
+                Ok(Txn::Dispute { client: r.client, tx: r.tx })
+            }
+            "resolve" => {
+                no_amount(r.amount, "resolve")?;
+                Ok(Txn::Resolve { client: r.client, tx: r.tx })
+            }
+            "chargeback" => {
+                no_amount(r.amount, "chargeback")?;
+                Ok(Txn::Chargeback { client: r.client, tx: r.tx })
+            }
+            other => Err(TxnParseError::UnknownType(other.to_string())),
+        }
+    }
+}
+
+fn positive_amount(amount: Option<Decimal>, kind: &'static str) -> Result<Decimal, TxnParseError> {
+    let amount = amount.ok_or(TxnParseError::MissingAmount(kind))?.round_dp(4);
+    if amount > Decimal::ZERO {
+        Ok(amount)
+    } else {
+        Err(TxnParseError::NonPositiveAmount(amount))
+    }
+}
+
+fn no_amount(amount: Option<Decimal>, kind: &'static str) -> Result<(), TxnParseError> {
+    match amount {
+        None => Ok(()),
+        Some(_) => Err(TxnParseError::UnexpectedAmount(kind)),
+    }
+}
+
+// deposit/withdrawal are different from dispute/resolve/chargeback
+// For the former, their tx refers to themselves
+#[derive(Clone, Copy, Debug)]
+enum TxnKind {
+    DepositKind,
+    WithdrawalKind,
+}
+
+// Lifecycle of a disputable (deposit/withdrawal) transaction. Only
+// Processed->Disputed, Disputed->Resolved and Disputed->ChargedBack are
+// legal; everything else is rejected by the caller instead of silently
+// applied. ChargedBack is terminal. We also treat Resolved as terminal
+// (no re-dispute) rather than allowing Resolved->Disputed again: once a
+// dispute has been resolved in the processor's favor, re-opening it would
+// let a client dispute the same tx indefinitely, so a second dispute on
+// the same tx requires a brand new transaction id from the partner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+#[derive(Debug)]
+pub struct AccountInfo {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub is_locked: bool,
+}
+
+impl Default for AccountInfo {
+    fn default() -> Self {
+        Self {
+            available: Decimal::ZERO,
+            held: Decimal::ZERO,
+            is_locked: false,
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerError {
+    #[error("client {0} does not have enough available funds for this withdrawal")]
+    NotEnoughFunds(ClientId),
+    #[error("client {0} has no transaction {1}")]
+    UnknownTx(ClientId, TxnId),
+    #[error("client {0} and transaction {1} belong to different clients")]
+    ClientMismatch(ClientId, TxnId),
+    #[error("transaction {0} was already submitted")]
+    DuplicateTx(TxnId),
+    #[error("transaction {0} is already disputed")]
+    AlreadyDisputed(TxnId),
+    #[error("transaction {0} was already resolved")]
+    AlreadyResolved(TxnId),
+    #[error("transaction {0} was already charged back")]
+    AlreadyChargedBack(TxnId),
+    #[error("transaction {0} is not currently disputed")]
+    NotDisputed(TxnId),
+    #[error("client {0}'s account is frozen")]
+    FrozenAccount(ClientId),
+}
+
+/// Holds all ledger state (accounts, the transactions that can be
+/// disputed, and their dispute lifecycle) and applies `Txn`s to it one at
+/// a time. This is the reusable core: `process_file` in `main.rs` is just
+/// a CSV-parsing wrapper around `process_transaction`.
+#[derive(Default)]
+pub struct Ledger {
+    accounts: HashMap<ClientId, AccountInfo>,
+    transactions: HashMap<TxnId, (TxnKind, ClientId, Decimal)>,
+    states: HashMap<TxnId, TxState>,
+    /// Running total of funds issued to each client: successful deposits
+    /// add, successful withdrawals subtract. A chargeback adjusts it by
+    /// the same amount it adjusts `held` by (see `process_transaction`),
+    /// since that's the money leaving or returning to the ledger for
+    /// good. Tracked per client, rather than as a single grand total, so
+    /// `audit` can point at which client's books don't add up.
+    issuance_by_client: HashMap<ClientId, Decimal>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process_transaction(&mut self, txn: Txn) -> Result<(), LedgerError> {
+        match txn {
+            Txn::Deposit { client, tx, amount } => {
+                let acct_info = self.accounts.entry(client).or_default();
+                if acct_info.is_locked {
+                    return Err(LedgerError::FrozenAccount(client));
+                }
+                if self.transactions.contains_key(&tx) {
+                    return Err(LedgerError::DuplicateTx(tx));
+                }
+                self.transactions.insert(tx, (TxnKind::DepositKind, client, amount));
+                self.states.insert(tx, TxState::Processed);
+                acct_info.available += amount;
+                *self.issuance_by_client.entry(client).or_insert(Decimal::ZERO) += amount;
+                Ok(())
+            }
+            Txn::Withdrawal { client, tx, amount } => {
+                let acct_info = self.accounts.entry(client).or_default();
+                if acct_info.is_locked {
+                    return Err(LedgerError::FrozenAccount(client));
+                }
+                if self.transactions.contains_key(&tx) {
+                    return Err(LedgerError::DuplicateTx(tx));
+                }
+                if amount > acct_info.available {
+                    return Err(LedgerError::NotEnoughFunds(client));
+                }
+                self.transactions.insert(tx, (TxnKind::WithdrawalKind, client, amount));
+                self.states.insert(tx, TxState::Processed);
+                acct_info.available -= amount;
+                *self.issuance_by_client.entry(client).or_insert(Decimal::ZERO) -= amount;
+                Ok(())
+            }
+            Txn::Dispute { client, tx } => {
+                let (txn_kind, client_id2, amount) = *self
+                    .transactions
+                    .get(&tx)
+                    .ok_or(LedgerError::UnknownTx(client, tx))?;
+                if client != client_id2 {
+                    return Err(LedgerError::ClientMismatch(client, tx));
+                }
+                match self.states.get(&tx) {
+                    Some(TxState::Processed) => {}
+                    Some(TxState::Disputed) => return Err(LedgerError::AlreadyDisputed(tx)),
+                    Some(TxState::Resolved) => return Err(LedgerError::AlreadyResolved(tx)),
+                    Some(TxState::ChargedBack) => return Err(LedgerError::AlreadyChargedBack(tx)),
+                    None => return Err(LedgerError::UnknownTx(client, tx)),
+                }
+                let acct_info = self.accounts.entry(client).or_default();
+                if acct_info.is_locked {
+                    return Err(LedgerError::FrozenAccount(client));
+                }
+                // Tentatively reverse the disputed transaction: a disputed
+                // deposit pulls the funds back out of `available`, while a
+                // disputed withdrawal credits them back (held can legitimately
+                // go negative here, since the money already left the account).
+                match txn_kind {
+                    TxnKind::DepositKind => {
+                        acct_info.available -= amount;
+                        acct_info.held += amount;
+                    }
+                    TxnKind::WithdrawalKind => {
+                        acct_info.available += amount;
+                        acct_info.held -= amount;
+                    }
+                }
+                self.states.insert(tx, TxState::Disputed);
+                Ok(())
+            }
+            Txn::Resolve { client, tx } => {
+                let (txn_kind, client_id2, amount) = *self
+                    .transactions
+                    .get(&tx)
+                    .ok_or(LedgerError::UnknownTx(client, tx))?;
+                if client != client_id2 {
+                    return Err(LedgerError::ClientMismatch(client, tx));
+                }
+                if self.states.get(&tx) != Some(&TxState::Disputed) {
+                    return Err(LedgerError::NotDisputed(tx));
+                }
+                let acct_info = self.accounts.entry(client).or_default();
+                if acct_info.is_locked {
+                    return Err(LedgerError::FrozenAccount(client));
+                }
+                // Undo the hold symmetrically, restoring pre-dispute balances.
+                match txn_kind {
+                    TxnKind::DepositKind => {
+                        acct_info.available += amount;
+                        acct_info.held -= amount;
+                    }
+                    TxnKind::WithdrawalKind => {
+                        acct_info.available -= amount;
+                        acct_info.held += amount;
+                    }
+                }
+                self.states.insert(tx, TxState::Resolved);
+                Ok(())
+            }
+            Txn::Chargeback { client, tx } => {
+                let (txn_kind, client_id2, amount) = *self
+                    .transactions
+                    .get(&tx)
+                    .ok_or(LedgerError::UnknownTx(client, tx))?;
+                if client != client_id2 {
+                    return Err(LedgerError::ClientMismatch(client, tx));
+                }
+                if self.states.get(&tx) != Some(&TxState::Disputed) {
+                    return Err(LedgerError::NotDisputed(tx));
+                }
+                let acct_info = self.accounts.entry(client).or_default();
+                if acct_info.is_locked {
+                    return Err(LedgerError::FrozenAccount(client));
+                }
+                // Make the reversal permanent: a deposit's held funds are
+                // clawed back, a withdrawal's held funds are permanently
+                // returned to the client.
+                let issuance_delta = match txn_kind {
+                    TxnKind::DepositKind => -amount,
+                    TxnKind::WithdrawalKind => amount,
+                };
+                acct_info.held += issuance_delta;
+                acct_info.is_locked = true;
+                *self.issuance_by_client.entry(client).or_insert(Decimal::ZERO) += issuance_delta;
+                self.states.insert(tx, TxState::ChargedBack);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = (ClientId, &AccountInfo)> {
+        self.accounts.iter().map(|(id, info)| (*id, info))
+    }
+
+    /// Like `accounts`, but consumes the ledger. Used by the sequential CLI
+    /// path to print the final balances; the sharded path uses `into_parts`.
+    pub fn into_accounts(self) -> impl Iterator<Item = (ClientId, AccountInfo)> {
+        self.accounts.into_iter()
+    }
+
+    /// Consumes the ledger, splitting it into its account balances and
+    /// per-client issuance. Used to merge several workers' ledgers back
+    /// together in the `--threads` CLI path before printing and auditing.
+    pub fn into_parts(self) -> (HashMap<ClientId, AccountInfo>, HashMap<ClientId, Decimal>) {
+        (self.accounts, self.issuance_by_client)
+    }
+
+    /// Checks the conservation invariant: for every client, the funds
+    /// issued to them (deposits minus withdrawals, minus charged-back
+    /// deposits, plus charged-back withdrawals) should equal
+    /// `available + held`. Returns every client for which
+    /// that doesn't hold; an empty vec means the books balance.
+    pub fn audit(&self) -> Vec<AuditMismatch> {
+        audit_mismatches(&self.accounts, &self.issuance_by_client)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("client {client} issuance mismatch: expected {expected} (issuance), found {actual} (available + held)")]
+pub struct AuditMismatch {
+    pub client: ClientId,
+    pub expected: Decimal,
+    pub actual: Decimal,
+}
+
+/// Shared by `Ledger::audit` and by the `--threads` CLI path, which joins
+/// several workers' `accounts`/`issuance_by_client` maps before auditing
+/// the merged result.
+pub fn audit_mismatches(
+    accounts: &HashMap<ClientId, AccountInfo>,
+    issuance_by_client: &HashMap<ClientId, Decimal>,
+) -> Vec<AuditMismatch> {
+    accounts
+        .iter()
+        .filter_map(|(client, info)| {
+            let expected = *issuance_by_client.get(client).unwrap_or(&Decimal::ZERO);
+            let actual = info.available + info.held;
+            (expected != actual).then_some(AuditMismatch {
+                client: *client,
+                expected,
+                actual,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dec(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    fn account(ledger: &Ledger, client: ClientId) -> &AccountInfo {
+        ledger.accounts.get(&client).unwrap()
+    }
+
+    #[test]
+    fn deposit_dispute_resolve_restores_balance() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process_transaction(Txn::Deposit { client: 1, tx: 1, amount: dec("5") })
+            .unwrap();
+        ledger.process_transaction(Txn::Dispute { client: 1, tx: 1 }).unwrap();
+        let acct = account(&ledger, 1);
+        assert_eq!(acct.available, dec("0"));
+        assert_eq!(acct.held, dec("5"));
+
+        ledger.process_transaction(Txn::Resolve { client: 1, tx: 1 }).unwrap();
+        let acct = account(&ledger, 1);
+        assert_eq!(acct.available, dec("5"));
+        assert_eq!(acct.held, dec("0"));
+        assert!(!acct.is_locked);
+    }
+
+    #[test]
+    fn deposit_dispute_chargeback_locks_and_claws_back() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process_transaction(Txn::Deposit { client: 1, tx: 1, amount: dec("5") })
+            .unwrap();
+        ledger.process_transaction(Txn::Dispute { client: 1, tx: 1 }).unwrap();
+        ledger.process_transaction(Txn::Chargeback { client: 1, tx: 1 }).unwrap();
+
+        let acct = account(&ledger, 1);
+        assert_eq!(acct.available, dec("0"));
+        assert_eq!(acct.held, dec("0"));
+        assert!(acct.is_locked);
+    }
+
+    #[test]
+    fn withdrawal_dispute_can_take_held_negative() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process_transaction(Txn::Deposit { client: 1, tx: 1, amount: dec("5") })
+            .unwrap();
+        ledger
+            .process_transaction(Txn::Withdrawal { client: 1, tx: 2, amount: dec("5") })
+            .unwrap();
+        ledger.process_transaction(Txn::Dispute { client: 1, tx: 2 }).unwrap();
+
+        let acct = account(&ledger, 1);
+        assert_eq!(acct.available, dec("5"));
+        assert_eq!(acct.held, dec("-5"));
+
+        ledger.process_transaction(Txn::Chargeback { client: 1, tx: 2 }).unwrap();
+        let acct = account(&ledger, 1);
+        assert_eq!(acct.available, dec("5"));
+        assert_eq!(acct.held, dec("0"));
+        assert!(acct.is_locked);
+    }
+
+    #[test]
+    fn dispute_rejected_once_already_disputed_resolved_or_charged_back() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process_transaction(Txn::Deposit { client: 1, tx: 1, amount: dec("5") })
+            .unwrap();
+        ledger.process_transaction(Txn::Dispute { client: 1, tx: 1 }).unwrap();
+        assert_eq!(
+            ledger.process_transaction(Txn::Dispute { client: 1, tx: 1 }),
+            Err(LedgerError::AlreadyDisputed(1))
+        );
+
+        ledger.process_transaction(Txn::Resolve { client: 1, tx: 1 }).unwrap();
+        assert_eq!(
+            ledger.process_transaction(Txn::Dispute { client: 1, tx: 1 }),
+            Err(LedgerError::AlreadyResolved(1))
+        );
+
+        ledger
+            .process_transaction(Txn::Deposit { client: 1, tx: 2, amount: dec("5") })
+            .unwrap();
+        ledger.process_transaction(Txn::Dispute { client: 1, tx: 2 }).unwrap();
+        ledger.process_transaction(Txn::Chargeback { client: 1, tx: 2 }).unwrap();
+        assert_eq!(
+            ledger.process_transaction(Txn::Dispute { client: 1, tx: 2 }),
+            Err(LedgerError::AlreadyChargedBack(2))
+        );
+    }
+
+    #[test]
+    fn resolve_and_chargeback_rejected_when_not_disputed() {
+        let mut ledger = Ledger::new();
+        ledger
+            .process_transaction(Txn::Deposit { client: 1, tx: 1, amount: dec("5") })
+            .unwrap();
+        assert_eq!(
+            ledger.process_transaction(Txn::Resolve { client: 1, tx: 1 }),
+            Err(LedgerError::NotDisputed(1))
+        );
+        assert_eq!(
+            ledger.process_transaction(Txn::Chargeback { client: 1, tx: 1 }),
+            Err(LedgerError::NotDisputed(1))
+        );
+    }
+
+    fn record(type_: &str, client: ClientId, tx: TxnId, amount: Option<&str>) -> TransactionRecord {
+        TransactionRecord {
+            type_: type_.to_string(),
+            client,
+            tx,
+            amount: amount.map(dec),
+        }
+    }
+
+    #[test]
+    fn parses_deposit_and_withdrawal() {
+        assert!(matches!(
+            Txn::try_from(record("deposit", 1, 1, Some("5.0"))),
+            Ok(Txn::Deposit { client: 1, tx: 1, amount }) if amount == dec("5.0")
+        ));
+        assert!(matches!(
+            Txn::try_from(record("withdrawal", 1, 2, Some("2.5"))),
+            Ok(Txn::Withdrawal { client: 1, tx: 2, amount }) if amount == dec("2.5")
+        ));
+    }
+
+    #[test]
+    fn parses_dispute_resolve_chargeback_without_amount() {
+        assert!(matches!(
+            Txn::try_from(record("dispute", 1, 1, None)),
+            Ok(Txn::Dispute { client: 1, tx: 1 })
+        ));
+        assert!(matches!(
+            Txn::try_from(record("resolve", 1, 1, None)),
+            Ok(Txn::Resolve { client: 1, tx: 1 })
+        ));
+        assert!(matches!(
+            Txn::try_from(record("chargeback", 1, 1, None)),
+            Ok(Txn::Chargeback { client: 1, tx: 1 })
+        ));
+    }
+
+    #[test]
+    fn rounds_amount_to_four_decimal_places() {
+        let txn = Txn::try_from(record("deposit", 1, 1, Some("1.23456789"))).unwrap();
+        assert!(matches!(txn, Txn::Deposit { amount, .. } if amount == dec("1.2346")));
+    }
+
+    #[test]
+    fn rejects_unknown_type() {
+        assert_eq!(
+            Txn::try_from(record("teleport", 1, 1, None)),
+            Err(TxnParseError::UnknownType("teleport".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_deposit_or_withdrawal_missing_amount() {
+        assert_eq!(
+            Txn::try_from(record("deposit", 1, 1, None)),
+            Err(TxnParseError::MissingAmount("deposit"))
+        );
+        assert_eq!(
+            Txn::try_from(record("withdrawal", 1, 1, None)),
+            Err(TxnParseError::MissingAmount("withdrawal"))
+        );
+    }
+
+    #[test]
+    fn rejects_dispute_resolve_chargeback_with_amount() {
+        assert_eq!(
+            Txn::try_from(record("dispute", 1, 1, Some("1.0"))),
+            Err(TxnParseError::UnexpectedAmount("dispute"))
+        );
+        assert_eq!(
+            Txn::try_from(record("resolve", 1, 1, Some("1.0"))),
+            Err(TxnParseError::UnexpectedAmount("resolve"))
+        );
+        assert_eq!(
+            Txn::try_from(record("chargeback", 1, 1, Some("1.0"))),
+            Err(TxnParseError::UnexpectedAmount("chargeback"))
+        );
+    }
+
+    #[test]
+    fn rejects_zero_or_negative_amount() {
+        assert_eq!(
+            Txn::try_from(record("deposit", 1, 1, Some("0"))),
+            Err(TxnParseError::NonPositiveAmount(dec("0")))
+        );
+        assert_eq!(
+            Txn::try_from(record("withdrawal", 1, 1, Some("-3.0"))),
+            Err(TxnParseError::NonPositiveAmount(dec("-3.0")))
+        );
+    }
+
+    #[test]
+    fn audit_mismatches_empty_when_books_balance() {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            1,
+            AccountInfo { available: dec("3"), held: dec("2"), is_locked: false },
+        );
+        let mut issuance = HashMap::new();
+        issuance.insert(1, dec("5"));
+
+        assert_eq!(audit_mismatches(&accounts, &issuance), vec![]);
+    }
+
+    #[test]
+    fn audit_mismatches_flags_client_whose_books_are_off() {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            1,
+            AccountInfo { available: dec("3"), held: dec("2"), is_locked: false },
+        );
+        let mut issuance = HashMap::new();
+        issuance.insert(1, dec("10"));
+
+        assert_eq!(
+            audit_mismatches(&accounts, &issuance),
+            vec![AuditMismatch { client: 1, expected: dec("10"), actual: dec("5") }]
+        );
+    }
+}
@@ -1,264 +1,204 @@
 use anyhow::{anyhow, Result};
-use csv::StringRecord;
-use rust_decimal::prelude::*;
-use std::collections::{HashMap, HashSet};
+use payments::{audit_mismatches, AccountInfo, AuditMismatch, ClientId, Ledger, TransactionRecord, Txn};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
+use std::sync::mpsc;
+use std::thread;
 
-struct AccountInfo {
-    available: Decimal,
-    held: Decimal,
-    is_locked: bool,
-}
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
 
-impl Default for AccountInfo {
-    fn default() -> Self {
-        Self {
-            available: Decimal::ZERO,
-            held: Decimal::ZERO,
-            is_locked: false,
+    let mut filename: Option<String> = None;
+    let mut threads = available_parallelism();
+    let mut audit = false;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--threads" => {
+                i += 1;
+                let value = args
+                    .get(i)
+                    .ok_or_else(|| anyhow!("--threads requires a value"))?;
+                threads = value.parse()?;
+            }
+            "--audit" => audit = true,
+            other => filename = Some(other.to_string()),
         }
+        i += 1;
     }
-}
 
-type ClientId = u16;
-type TxnId = u32;
-
-#[derive(Clone, Copy)]
-enum Txn {
-    Deposit {
-        client: ClientId,
-        tx: TxnId,
-        amount: Decimal,
-    },
-    Withdrawal {
-        client: ClientId,
-        tx: TxnId,
-        amount: Decimal,
-    },
-    Dispute {
-        client: ClientId,
-        tx: TxnId,
-    },
-    Resolve {
-        client: ClientId,
-        tx: TxnId,
-    },
-    Chargeback {
-        client: ClientId,
-        tx: TxnId,
-    },
-}
+    let Some(filename) = filename else {
+        return Ok(());
+    };
 
-// deposit/withdrawal are different from dispute/resolve/chargeback
-// For the former, their tx refers to themselves
-#[derive(Clone, Copy)]
-enum TxnKind {
-    DepositKind,
-    WithdrawalKind,
+    if threads <= 1 {
+        process_file(&filename, audit)
+    } else {
+        process_file_parallel(&filename, threads, audit)
+    }
 }
 
-fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() == 2 {
-        process_file(&args[1])
-    } else {
-        Ok(())
+fn report_audit(mismatches: &[AuditMismatch]) {
+    if mismatches.is_empty() {
+        eprintln!("audit: total issuance matches available + held for every client");
+        return;
+    }
+    for mismatch in mismatches {
+        eprintln!("audit: {}", mismatch);
     }
 }
 
-fn txn_of_string_record(r: &StringRecord) -> Result<Txn> {
-    if r.len() == 3 {
-        let client = r.get(1).unwrap().parse::<ClientId>()?;
-        let tx = r.get(2).unwrap().parse::<TxnId>()?;
-        match r.get(0) {
-            Some("dispute") => Ok(Txn::Dispute { client, tx }),
-            Some("resolve") => Ok(Txn::Resolve { client, tx }),
-            Some("chargeback") => Ok(Txn::Chargeback { client, tx }),
-            _ => Err(anyhow!("invalid param")),
-        }
-    } else if r.len() == 4 {
-        let client = r.get(1).unwrap().parse::<ClientId>()?;
-        let tx = r.get(2).unwrap().parse::<TxnId>()?;
-        let amount = r.get(3).unwrap().parse::<Decimal>()?.round_dp(4);
-        if amount > Decimal::ZERO {
-            match r.get(0) {
-                Some("deposit") => Ok(Txn::Deposit { client, tx, amount }),
-                Some("withdrawal") => Ok(Txn::Withdrawal { client, tx, amount }),
-                _ => Err(anyhow!("invalid param")),
-            }
-        } else {
-            Err(anyhow!("update amount less than or equal to zero"))
-        }
-    } else {
-        Err(anyhow!("invalid record"))
+fn available_parallelism() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+fn txn_client(txn: &Txn) -> ClientId {
+    match *txn {
+        Txn::Deposit { client, .. }
+        | Txn::Withdrawal { client, .. }
+        | Txn::Dispute { client, .. }
+        | Txn::Resolve { client, .. }
+        | Txn::Chargeback { client, .. } => client,
     }
 }
 
-fn process_file(filename: &str) -> Result<()> {
+fn reader_for(filename: &str) -> Result<csv::Reader<BufReader<File>>> {
     let file = File::open(filename)?;
     let reader = BufReader::new(file);
-
-    let mut rdr = csv::ReaderBuilder::new()
+    Ok(csv::ReaderBuilder::new()
         .flexible(true)
         .comment(Some(b'#'))
-        .from_reader(reader);
+        .trim(csv::Trim::All)
+        .from_reader(reader))
+}
 
-    let mut accounts: HashMap<ClientId, AccountInfo> = HashMap::new();
-    let mut transactions: HashMap<TxnId, (TxnKind, ClientId, Decimal)> = HashMap::new();
-    let mut disputed: HashSet<TxnId> = HashSet::new();
+fn build_ledger(filename: &str) -> Result<Ledger> {
+    let mut rdr = reader_for(filename)?;
+    let mut ledger = Ledger::new();
 
-    for result in rdr.records() {
-        if let Err(e) = result {
-            // Skip bad data lines
-            eprintln!("Bad record: {:?}", e);
-            continue;
-        }
-        let mut record = result.unwrap();
-        record.trim(); // Ensure that all fields are trimmed
-        eprintln!("record: {:?}", &record);
-        let txn = txn_of_string_record(&record);
-        if let Err(e) = txn {
-            // Skip bad data lines
-            eprintln!("Bad txn: {:?}", e);
-            continue;
-        }
-        match txn.unwrap() {
-            Txn::Deposit { client, tx, amount } => {
-                let acct_info = accounts.entry(client).or_insert_with(AccountInfo::default);
-                if acct_info.is_locked {
-                    eprintln!("Client account {:?} is locked, skipping", client);
-                    continue;
-                }
-                // We need to keep all transactions in case they're disputed.
-                // No guidance is given on how to behave differently with respect to
-                // deposits vs withdrawals.
-                if transactions.get(&tx).is_none() {
-                    transactions.insert(tx, (TxnKind::DepositKind, client, amount));
-                } else {
-                    eprintln!("Duplicate txn in record {:?}", &record);
-                    continue;
-                }
-                acct_info.available += amount;
+    for result in rdr.deserialize::<TransactionRecord>() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                // Skip bad data lines
+                eprintln!("Bad record: {:?}", e);
+                continue;
             }
-            Txn::Withdrawal { client, tx, amount } => {
-                let acct_info = accounts.entry(client).or_insert_with(AccountInfo::default);
-                if acct_info.is_locked {
-                    eprintln!("Client account {:?} is locked, skipping", client);
-                    continue;
-                }
-                // We need to keep all transactions in case they're disputed.
-                // No guidance is given on how to behave differently with respect to
-                // deposits vs withdrawals.
-                if transactions.get(&tx).is_none() {
-                    transactions.insert(tx, (TxnKind::WithdrawalKind, client, amount));
-                } else {
-                    eprintln!("Duplicate txn in record {:?}", &record);
-                    continue;
-                }
-                if amount <= acct_info.available {
-                    acct_info.available -= amount;
-                } else {
-                    eprintln!("Attempt to withdraw more than available {:?}", &record);
-                    continue;
-                }
+        };
+        let txn = match Txn::try_from(record) {
+            Ok(txn) => txn,
+            Err(e) => {
+                // Skip bad data lines
+                eprintln!("Bad txn: {:?}", e);
+                continue;
             }
-            Txn::Dispute { client, tx } => {
-                let txn = transactions.get(&tx);
-                if txn.is_none() {
-                    // Ignore and assume it's an error on partners side
-                    continue;
-                }
-                // The fact that _txn_kind is not used indicates a flaw. According
-                // to the problem description, disputed deposits and withdrawals
-                // are handled the same.
-                let (_txn_kind, client_id2, amount) = txn.unwrap();
-                if client != *client_id2 {
-                    eprintln!(
-                        "transaction {} clients don't match, {} and {}",
-                        tx, client, *client_id2
-                    );
-                    continue;
-                }
-                let acct_info = accounts.entry(client).or_insert_with(AccountInfo::default);
-                if acct_info.is_locked {
-                    eprintln!("Client account {:?} is locked, skipping", client);
-                    continue;
-                }
-                if acct_info.available >= *amount {
-                    acct_info.available -= amount;
-                    acct_info.held += amount;
-                    disputed.insert(tx);
-                } else {
-                    eprintln!("'dispute' without enough available {:?}", &record);
-                    continue;
+        };
+        if let Err(e) = ledger.process_transaction(txn) {
+            eprintln!("Rejected txn: {}", e);
+            continue;
+        }
+    }
+
+    Ok(ledger)
+}
+
+fn process_file(filename: &str, audit: bool) -> Result<()> {
+    let ledger = build_ledger(filename)?;
+
+    if audit {
+        report_audit(&ledger.audit());
+    }
+    print_accounts(ledger.into_accounts());
+    Ok(())
+}
+
+/// Multithreaded counterpart to `process_file`. Transactions for a given
+/// client are fully independent of transactions for any other client, so
+/// we shard clients across `threads` workers (`hash(client) % threads`),
+/// each owning its own `Ledger`. The CSV reader stays single-threaded and
+/// dispatches records over per-worker channels, which preserves arrival
+/// order within each client and keeps dispute/resolve/chargeback
+/// semantics correct. Once the reader hits EOF the channels are dropped,
+/// the workers drain and exit, and their account maps are merged (they're
+/// disjoint by construction, so this is just a union) for output.
+fn build_sharded(
+    filename: &str,
+    threads: usize,
+) -> Result<(HashMap<ClientId, AccountInfo>, HashMap<ClientId, Decimal>)> {
+    let mut rdr = reader_for(filename)?;
+
+    let mut senders = Vec::with_capacity(threads);
+    let mut handles = Vec::with_capacity(threads);
+    for _ in 0..threads {
+        let (sender, receiver) = mpsc::channel::<Txn>();
+        let handle = thread::spawn(move || {
+            let mut ledger = Ledger::new();
+            for txn in receiver {
+                if let Err(e) = ledger.process_transaction(txn) {
+                    eprintln!("Rejected txn: {}", e);
                 }
             }
-            Txn::Resolve { client, tx } => {
-                let txn = transactions.get(&tx);
-                if txn.is_none() {
-                    // Ignore and assume it's an error on partners side
-                    continue;
-                }
-                // The fact that _txn_kind is not used indicates a flaw. According
-                // to the problem description, disputed deposits and withdrawals
-                // are handled the same.
-                let (_txn_kind, client_id2, amount) = txn.unwrap();
-                if client != *client_id2 {
-                    eprintln!(
-                        "transaction {} clients don't match, {} and {}",
-                        tx, client, *client_id2
-                    );
-                    continue;
-                }
-                let acct_info = accounts.entry(client).or_insert_with(AccountInfo::default);
-                if acct_info.is_locked {
-                    eprintln!("Client account {:?} is locked, skipping", client);
-                    continue;
-                }
-                if !disputed.contains(&tx) {
-                    eprintln!("'resolve' called on undisputed transaction {}", tx);
-                    continue;
-                } else {
-                    acct_info.available += amount;
-                    acct_info.held -= amount;
-                    disputed.remove(&tx);
-                }
+            ledger
+        });
+        senders.push(sender);
+        handles.push(handle);
+    }
+
+    for result in rdr.deserialize::<TransactionRecord>() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                eprintln!("Bad record: {:?}", e);
+                continue;
             }
-            Txn::Chargeback { client, tx } => {
-                let txn = transactions.get(&tx);
-                if txn.is_none() {
-                    // Ignore and assume it's an error on partners side
-                    continue;
-                }
-                // The fact that _txn_kind is not used indicates a flaw. According
-                // to the problem description, disputed deposits and withdrawals
-                // are handled the same.
-                let (_txn_kind, client_id2, amount) = txn.unwrap();
-                if client != *client_id2 {
-                    eprintln!(
-                        "transaction {} clients don't match, {} and {}",
-                        tx, client, *client_id2
-                    );
-                    continue;
-                }
-                let acct_info = accounts.entry(client).or_insert_with(AccountInfo::default);
-                if acct_info.is_locked {
-                    eprintln!("Client account {:?} is locked, skipping", client);
-                    continue;
-                }
-                if !disputed.contains(&tx) {
-                    eprintln!("'chargeback' called on undisputed transaction {}", tx);
-                    continue;
-                } else {
-                    acct_info.held -= amount;
-                    acct_info.is_locked = true;
-                    disputed.remove(&tx);
-                }
+        };
+        let txn = match Txn::try_from(record) {
+            Ok(txn) => txn,
+            Err(e) => {
+                eprintln!("Bad txn: {:?}", e);
+                continue;
             }
-        }
+        };
+        // Routed by the record's own `client` field, which is trusted here.
+        // A malformed dispute/resolve/chargeback that names the wrong client
+        // for a real tx therefore lands on a shard that never processed
+        // that tx (unless both clients map to the same worker), so it's
+        // rejected as UnknownTx here instead of the ClientMismatch
+        // sequential mode would give for the same input. No
+        // balances diverge either way since the record is rejected either
+        // way; only the logged rejection reason differs between modes.
+        let worker = txn_client(&txn) as usize % threads;
+        senders[worker]
+            .send(txn)
+            .expect("worker thread exited before EOF");
+    }
+    drop(senders);
+
+    let mut accounts: HashMap<ClientId, AccountInfo> = HashMap::new();
+    let mut issuance_by_client: HashMap<ClientId, Decimal> = HashMap::new();
+    for handle in handles {
+        let ledger = handle.join().expect("worker thread panicked");
+        let (worker_accounts, worker_issuance) = ledger.into_parts();
+        accounts.extend(worker_accounts);
+        issuance_by_client.extend(worker_issuance);
+    }
+
+    Ok((accounts, issuance_by_client))
+}
+
+fn process_file_parallel(filename: &str, threads: usize, audit: bool) -> Result<()> {
+    let (accounts, issuance_by_client) = build_sharded(filename, threads)?;
+
+    if audit {
+        report_audit(&audit_mismatches(&accounts, &issuance_by_client));
     }
+    print_accounts(accounts.into_iter());
+    Ok(())
+}
 
+fn print_accounts(accounts: impl Iterator<Item = (ClientId, AccountInfo)>) {
     println!("\n");
     let (client, available, held, total, locked) =
         ("client", "available", "held", "total", "locked");
@@ -273,6 +213,75 @@ fn process_file(filename: &str) -> Result<()> {
         let locked = acct_info.is_locked;
         println!("{client_id:16}, {available:16}, {held:16}, {total:16}, {locked:16}");
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_temp_csv(name: &str, contents: &str) -> std::path::PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("payments_test_{}_{}.csv", name, std::process::id()));
+        File::create(&path).unwrap().write_all(contents.as_bytes()).unwrap();
+        path
+    }
+
+    fn balances(
+        accounts: impl Iterator<Item = (ClientId, AccountInfo)>,
+    ) -> HashMap<ClientId, (Decimal, Decimal, bool)> {
+        accounts
+            .map(|(client, info)| (client, (info.available, info.held, info.is_locked)))
+            .collect()
+    }
+
+    #[test]
+    fn sequential_and_sharded_agree() {
+        let csv = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,10.0
+deposit,1,3,3.0
+withdrawal,1,4,2.0
+dispute,1,3
+resolve,1,3
+dispute,1,4
+chargeback,1,4
+deposit,3,5,7.0
+withdrawal,2,6,4.0
+dispute,2,2
+chargeback,2,2
+";
+        let path = write_temp_csv("agree", csv);
+        let filename = path.to_str().unwrap();
+
+        let sequential = balances(build_ledger(filename).unwrap().into_accounts());
+        let (sharded_accounts, _) = build_sharded(filename, 4).unwrap();
+        let sharded = balances(sharded_accounts.into_iter());
+
+        std::fs::remove_file(&path).ok();
+        assert_eq!(sequential, sharded);
+    }
+
+    #[test]
+    fn audit_is_clean_for_a_well_formed_file_sequential_and_sharded() {
+        let csv = "\
+type,client,tx,amount
+deposit,1,1,5.0
+deposit,2,2,10.0
+withdrawal,1,3,2.0
+dispute,2,2
+chargeback,2,2
+";
+        let path = write_temp_csv("audit", csv);
+        let filename = path.to_str().unwrap();
+
+        let ledger = build_ledger(filename).unwrap();
+        assert_eq!(ledger.audit(), vec![]);
+
+        let (sharded_accounts, sharded_issuance) = build_sharded(filename, 4).unwrap();
+        assert_eq!(audit_mismatches(&sharded_accounts, &sharded_issuance), vec![]);
+
+        std::fs::remove_file(&path).ok();
+    }
 }